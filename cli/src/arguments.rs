@@ -1,8 +1,9 @@
 use clap::{App, Arg};
-use lz4jb::Context as Lz4Context;
+use lz4jb::{Context as Lz4Context, Lz4BlockOutput, Lz4BlockParallelOutput};
 
 use std::ffi::OsStr;
 use std::fmt;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
 const DEFAULT_EXTENSION: &str = "lz4";
@@ -29,9 +30,17 @@ const AVAILABLE_LIBRARIES: [(&str, Option<Lz4Context>, &str); 2] = [
     ),
 ];
 
+/// Supported range for the `--level` compression effort: `0` is the fast path, `1` to `12` ask
+/// for LZ4 high-compression (HC) at an increasing cost in CPU time.
+const COMPRESSION_LEVEL_RANGE: std::ops::RangeInclusive<i32> = 0..=12;
+
 #[derive(Debug, Copy, Clone)]
 pub(crate) enum Mode {
-    Compress { block_size: Option<usize> },
+    Compress {
+        block_size: Option<usize>,
+        processes: Option<usize>,
+        level: Option<i32>,
+    },
     Decompress,
     List,
     Test,
@@ -39,7 +48,11 @@ pub(crate) enum Mode {
 impl fmt::Display for Mode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::Compress { block_size: _ } => write!(f, "compress"),
+            Self::Compress {
+                block_size: _,
+                processes: _,
+                level: _,
+            } => write!(f, "compress"),
             Self::Decompress => write!(f, "decompress"),
             Self::List => write!(f, "list"),
             Self::Test => write!(f, "test"),
@@ -117,9 +130,89 @@ pub(crate) struct Arguments {
     pub(crate) mode: Mode,
     pub(crate) keep_input: bool,
     pub(crate) force: bool,
+    pub(crate) write_index: bool,
     pub(crate) lz4jb_context: Lz4Context,
 }
 
+/// Recursively collect every regular file under `path`, in a stable order. If `path` is not a
+/// directory, it is returned unchanged so single-file behavior (including later reporting on a
+/// missing file) is unaffected.
+///
+/// Symlinks are never followed while recursing: `std::fs::symlink_metadata` (unlike
+/// `Path::is_dir`) does not traverse them, so a symlink to a directory is treated as an opaque
+/// file instead of being walked into, which would otherwise let a symlink back to one of its own
+/// ancestors recurse forever.
+fn expand_directory(path: &Path) -> Result<Vec<PathBuf>, &'static str> {
+    let is_dir = std::fs::symlink_metadata(path)
+        .map(|metadata| metadata.is_dir())
+        .unwrap_or(false);
+    if !is_dir {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut entries = std::fs::read_dir(path)
+        .map_err(|_| "could not read directory")?
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|_| "could not read directory entry")?;
+    entries.sort_by_key(std::fs::DirEntry::path);
+    let mut files = Vec::new();
+    for entry in entries {
+        files.extend(expand_directory(&entry.path())?);
+    }
+    Ok(files)
+}
+
+/// Build the compressing [`Write`] described by `args.mode`: the block size and `--level` effort
+/// requested go to either [`Lz4BlockOutput`], or to [`Lz4BlockParallelOutput`] with the requested
+/// worker count when `--processes` asked for more than one; both honor `--level`.
+/// `args.write_index`'s footer is only ever produced by [`Lz4BlockOutput`]: `parse_cli()` rejects
+/// `--index` together with `--processes` above `1`, since the parallel writer has no index
+/// support.
+///
+/// # Errors
+///
+/// It will return an error if `args.mode` is not [`Mode::Compress`], or if the requested block
+/// size is out of range. A process count of `0` or `1` is not an error: it selects
+/// [`Lz4BlockOutput`].
+pub(crate) fn build_output_writer<W: Write + Send + 'static>(
+    args: &Arguments,
+    w: W,
+) -> std::io::Result<Box<dyn Write + Send>> {
+    let (block_size, processes, level) = match args.mode {
+        Mode::Compress {
+            block_size,
+            processes,
+            level,
+        } => (
+            block_size.unwrap_or_else(Lz4BlockOutput::<W>::default_block_size),
+            processes,
+            level,
+        ),
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "build_output_writer() is only valid for Mode::Compress",
+            ))
+        }
+    };
+
+    match processes {
+        Some(processes) if processes > 1 => Ok(Box::new(Lz4BlockParallelOutput::with_context(
+            w,
+            args.lz4jb_context,
+            block_size,
+            processes,
+            level,
+        )?)),
+        _ => Ok(Box::new(Lz4BlockOutput::with_context(
+            w,
+            args.lz4jb_context,
+            block_size,
+            level,
+            args.write_index,
+        )?)),
+    }
+}
+
 fn get_library(name: &str) -> Option<Lz4Context> {
     AVAILABLE_LIBRARIES
         .iter()
@@ -216,6 +309,45 @@ pub(crate) fn parse_cli() -> Result<Arguments, &'static str> {
                 .help("Block size for compression in bytes (between 64 and 33554432).")
                 .display_order(100),
         )
+        .arg(
+            Arg::with_name("processes")
+                .short("p")
+                .long("processes")
+                .takes_value(true)
+                .conflicts_with_all(&["decompress", "list", "test"])
+                .help("Number of worker threads in the compression pool (default: compress serially on the calling thread). Each block is waited for before the next one is handed to the pool, so blocks are still compressed one at a time. Not compatible with --index when set above 1, since the parallel writer has no index support.")
+                .display_order(100),
+        )
+        .arg(
+            Arg::with_name("level")
+                .short("#")
+                .long("level")
+                .takes_value(true)
+                .conflicts_with_all(&["decompress", "list", "test"])
+                .help("Compression effort, between 0 (fast, default) and 12 (maximum ratio, LZ4 HC).")
+                .validator(|v| {
+                    v.parse::<i32>()
+                        .ok()
+                        .filter(|level| COMPRESSION_LEVEL_RANGE.contains(level))
+                        .map(|_| ())
+                        .ok_or_else(|| {
+                            format!(
+                                "level {} is out of range: expected a value between {} and {}",
+                                v,
+                                COMPRESSION_LEVEL_RANGE.start(),
+                                COMPRESSION_LEVEL_RANGE.end()
+                            )
+                        })
+                })
+                .display_order(100),
+        )
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .conflicts_with_all(&["decompress", "list", "test"])
+                .help("Append a footer indexing every block's offset, for cheap random access and fast --list. Readers that don't understand it simply ignore the trailing bytes. Not compatible with --processes above 1: the parallel writer does not produce an index.")
+                .display_order(100),
+        )
         .arg(
             Arg::with_name("library")
                 .short("L")
@@ -239,7 +371,7 @@ pub(crate) fn parse_cli() -> Result<Arguments, &'static str> {
         .arg(
             Arg::with_name("file")
                 .help("Sets the input file to use.")
-                .long_help("Sets the input files to use. By default read from stdin and write to stdout.\nThe output file is determined this way:\n - <file>.<extension> when compressing\n - <file> with the .<extension> removed when decompressing")
+                .long_help("Sets the input files to use. By default read from stdin and write to stdout.\nA directory is walked recursively and every regular file found is processed, preserving its original name.\nThe output file is determined this way:\n - <file>.<extension> when compressing\n - <file> with the .<extension> removed when decompressing")
                 .multiple(true),
         );
 
@@ -260,6 +392,18 @@ pub(crate) fn parse_cli() -> Result<Arguments, &'static str> {
                 Ok(b) => b,
                 Err(_) => return Err("could not parse blocksize"),
             },
+            processes: match matches
+                .value_of("processes")
+                .map(str::parse::<usize>)
+                .transpose()
+            {
+                Ok(p) => p,
+                Err(_) => return Err("could not parse processes"),
+            },
+            level: match matches.value_of("level").map(str::parse::<i32>).transpose() {
+                Ok(l) => l,
+                Err(_) => return Err("could not parse level"),
+            },
         },
         (false, true, false, false) => Mode::Decompress,
         (false, false, true, false) => Mode::List,
@@ -275,21 +419,49 @@ pub(crate) fn parse_cli() -> Result<Arguments, &'static str> {
     let to_stdout = matches.is_present("stdout");
     let keep_input = matches.is_present("keep");
     let force = matches.is_present("force");
-    let files = matches
+    let write_index = matches.is_present("index");
+    if write_index {
+        if let Mode::Compress {
+            processes: Some(processes),
+            ..
+        } = mode
+        {
+            if processes > 1 {
+                return Err("--index is not compatible with --processes above 1: the parallel writer does not produce an index");
+            }
+        }
+    }
+    let file_args = matches
         .values_of_os("file")
         .into_iter()
         .flatten()
         .map(Path::new)
+        .collect::<Vec<_>>();
+    let expanded_files = file_args
+        .iter()
+        .map(|f| expand_directory(f))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    if !file_args.is_empty() && expanded_files.is_empty() {
+        return Err("no input files found in the given directories");
+    }
+    let files = expanded_files
+        .into_iter()
         .map(|f| {
+            let file_out = match mode {
+                Mode::Compress {
+                    block_size: _,
+                    processes: _,
+                    level: _,
+                } => FileDesc::compressed(&f, extension, to_stdout)?,
+                Mode::Decompress => FileDesc::decompressed(&f, extension, to_stdout)?,
+                _ => FileDesc::None,
+            };
             Ok(Files {
-                file_in: FileDesc::Filename(f.into()),
-                file_out: match mode {
-                    Mode::Compress { block_size: _ } => {
-                        FileDesc::compressed(f, extension, to_stdout)?
-                    }
-                    Mode::Decompress => FileDesc::decompressed(f, extension, to_stdout)?,
-                    _ => FileDesc::None,
-                },
+                file_in: FileDesc::Filename(f),
+                file_out,
             })
         })
         .collect::<Result<Vec<_>, _>>()?;
@@ -307,6 +479,7 @@ pub(crate) fn parse_cli() -> Result<Arguments, &'static str> {
         mode,
         keep_input,
         force,
+        write_index,
         lz4jb_context,
     })
 }
@@ -314,9 +487,161 @@ pub(crate) fn parse_cli() -> Result<Arguments, &'static str> {
 #[cfg(test)]
 mod test_arguments {
 
-    use super::FileDesc;
+    use super::{build_output_writer, expand_directory, Arguments, FileDesc, Mode};
+    use lz4jb::Context as Lz4Context;
     use std::ffi::OsStr;
+    use std::io::Write;
     use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    /// A `Write + Send + 'static` sink: `build_output_writer()` may hand `W` to the
+    /// thread-spawning parallel writer, so a borrowed `&mut Vec<u8>` won't do.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.lock().unwrap().flush()
+        }
+    }
+
+    fn compress_args(processes: Option<usize>, write_index: bool) -> Arguments {
+        Arguments {
+            files: Vec::new(),
+            mode: Mode::Compress {
+                block_size: None,
+                processes,
+                level: None,
+            },
+            keep_input: false,
+            force: false,
+            write_index,
+            lz4jb_context: Lz4Context::default(),
+        }
+    }
+
+    #[test]
+    fn build_output_writer_picks_serial_by_default() {
+        let args = compress_args(None, false);
+        let out = SharedBuf::default();
+        {
+            let mut writer = build_output_writer(&args, out.clone()).unwrap();
+            writer.write_all(b"...").unwrap();
+        }
+        assert!(!out.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn build_output_writer_picks_parallel_when_requested() {
+        let args = compress_args(Some(2), false);
+        let out = SharedBuf::default();
+        {
+            let mut writer = build_output_writer(&args, out.clone()).unwrap();
+            writer.write_all(b"...").unwrap();
+        }
+        assert!(!out.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn build_output_writer_appends_index_footer() {
+        let args = compress_args(None, true);
+        let out = SharedBuf::default();
+        {
+            let mut writer = build_output_writer(&args, out.clone()).unwrap();
+            writer.write_all(b"...").unwrap();
+        }
+        assert!(out
+            .0
+            .lock()
+            .unwrap()
+            .windows(8)
+            .any(|window| window == b"LZ4JBIDX"));
+    }
+
+    #[test]
+    fn build_output_writer_appends_index_footer_with_one_process() {
+        // --processes 1 still takes the serial path (see build_output_writer's `Some(processes)
+        // if processes > 1` guard), so it is fine to combine with --index.
+        let args = compress_args(Some(1), true);
+        let out = SharedBuf::default();
+        {
+            let mut writer = build_output_writer(&args, out.clone()).unwrap();
+            writer.write_all(b"...").unwrap();
+        }
+        assert!(out
+            .0
+            .lock()
+            .unwrap()
+            .windows(8)
+            .any(|window| window == b"LZ4JBIDX"));
+    }
+
+    #[test]
+    fn expand_directory_returns_single_file_unchanged() {
+        assert_eq!(
+            expand_directory(Path::new("some/file.txt")).unwrap(),
+            vec![Path::new("some/file.txt").to_path_buf()]
+        );
+    }
+
+    #[test]
+    fn expand_directory_walks_nested_directories() {
+        let root = std::env::temp_dir().join(format!(
+            "lz4jb-test-expand-directory-{:?}",
+            std::thread::current().id()
+        ));
+        let nested = root.join("nested");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(root.join("a.txt"), b"a").unwrap();
+        std::fs::write(nested.join("b.txt"), b"b").unwrap();
+
+        let mut files = expand_directory(&root).unwrap();
+        files.sort();
+        assert_eq!(files, vec![root.join("a.txt"), nested.join("b.txt")]);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn expand_directory_does_not_follow_a_symlink_cycle() {
+        let root = std::env::temp_dir().join(format!(
+            "lz4jb-test-expand-directory-cycle-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&root).unwrap();
+        let cycle = root.join("cycle");
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&root, &cycle).unwrap();
+
+        #[cfg(unix)]
+        {
+            // Would recurse forever (or stack overflow) before the symlink_metadata fix.
+            assert!(expand_directory(&root).is_ok());
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn expand_directory_returns_no_files_for_an_empty_directory() {
+        let root = std::env::temp_dir().join(format!(
+            "lz4jb-test-expand-empty-directory-{:?}",
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&root).unwrap();
+
+        // parse_cli() turns this into a hard error instead of silently falling back to stdio,
+        // since the user did name an input directory.
+        assert_eq!(
+            expand_directory(&root).unwrap(),
+            Vec::<std::path::PathBuf>::new()
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 
     #[test]
     fn filedesc_decompressed_basic() {
@@ -0,0 +1,191 @@
+use crate::common::{ErrorInternal, Result};
+
+use std::os::raw::{c_char, c_int};
+use std::result::Result as StdResult;
+
+/// Abstraction over the LZ4 block (de)compression backend used by [`crate::lz4_block_output`]
+/// and [`crate::lz4_block_seekable_input`].
+///
+/// Implementors only need to provide the fast compression path, decompression and the worst
+/// case output size; [`Self::compress_level`] defaults to ignoring `level` and falling back to
+/// [`Self::compress`], which is the correct behavior for a backend with no high-compression
+/// mode.
+pub trait Compression {
+    /// Worst case size of a `decompressed_len`-byte block once compressed.
+    fn get_maximum_compressed_buffer_len(&self, decompressed_len: usize) -> usize;
+
+    /// Compress `src` into `dst` on the fast path, returning the number of bytes written.
+    fn compress(&self, src: &[u8], dst: &mut [u8]) -> StdResult<usize, ErrorInternal>;
+
+    /// Compress `src` into `dst`, trading CPU time for ratio according to `level`.
+    ///
+    /// `None` and `Some(0)` must behave exactly like [`Self::compress`].
+    #[inline]
+    fn compress_level(
+        &self,
+        level: Option<i32>,
+        src: &[u8],
+        dst: &mut [u8],
+    ) -> StdResult<usize, ErrorInternal> {
+        let _ = level;
+        self.compress(src, dst)
+    }
+
+    /// Decompress `src` into `dst`, returning the number of bytes written.
+    fn decompress(&self, src: &[u8], dst: &mut [u8]) -> StdResult<usize, ErrorInternal>;
+}
+
+/// Range of `--level` routed to `LZ4_compress_HC` by [`Context::Lz4Sys`]: `1` is the fastest HC
+/// setting, `12` the maximum ratio. `0` (or no level at all) keeps the regular fast path.
+pub const LZ4_HC_LEVEL_RANGE: std::ops::RangeInclusive<i32> = 1..=12;
+
+/// Built-in [`Compression`] implementations, selectable from the CLI with `-L`/`--library`.
+#[derive(Debug, Copy, Clone)]
+pub enum Context {
+    /// Pure-Rust implementation backed by the [`lz4_flex`](https://crates.io/crates/lz4_flex) crate.
+    Lz4Flex,
+    /// Bindings to the reference `liblz4` via the [`lz4-sys`](https://crates.io/crates/lz4-sys) crate.
+    Lz4Sys,
+}
+
+impl Default for Context {
+    #[cfg(feature = "lz4-sys")]
+    fn default() -> Self {
+        Self::Lz4Sys
+    }
+    #[cfg(all(not(feature = "lz4-sys"), feature = "lz4_flex"))]
+    fn default() -> Self {
+        Self::Lz4Flex
+    }
+}
+
+impl Compression for Context {
+    fn get_maximum_compressed_buffer_len(&self, decompressed_len: usize) -> usize {
+        match self {
+            #[cfg(feature = "lz4_flex")]
+            Self::Lz4Flex => lz4_flex::block::get_maximum_output_size(decompressed_len),
+            #[cfg(feature = "lz4-sys")]
+            Self::Lz4Sys => unsafe {
+                lz4_sys::LZ4_compressBound(decompressed_len as c_int) as usize
+            },
+            #[allow(unreachable_patterns)]
+            _ => decompressed_len,
+        }
+    }
+
+    fn compress(&self, src: &[u8], dst: &mut [u8]) -> StdResult<usize, ErrorInternal> {
+        self.compress_level(None, src, dst)
+    }
+
+    fn compress_level(
+        &self,
+        level: Option<i32>,
+        src: &[u8],
+        dst: &mut [u8],
+    ) -> StdResult<usize, ErrorInternal> {
+        match self {
+            #[cfg(feature = "lz4_flex")]
+            Self::Lz4Flex => {
+                // lz4_flex has no high-compression mode: any requested effort keeps the fast path.
+                let _ = level;
+                match lz4_flex::block::compress_into(src, dst) {
+                    Ok(written) => Ok(written),
+                    Err(_) => ErrorInternal::new_error("lz4_flex compression failed"),
+                }
+            }
+            #[cfg(feature = "lz4-sys")]
+            Self::Lz4Sys => {
+                let hc_level = level.filter(|level| *level > 0).map(|level| {
+                    level.clamp(*LZ4_HC_LEVEL_RANGE.start(), *LZ4_HC_LEVEL_RANGE.end())
+                });
+                let written = unsafe {
+                    match hc_level {
+                        Some(level) => lz4_sys::LZ4_compress_HC(
+                            src.as_ptr() as *const c_char,
+                            dst.as_mut_ptr() as *mut c_char,
+                            src.len() as c_int,
+                            dst.len() as c_int,
+                            level,
+                        ),
+                        None => lz4_sys::LZ4_compress_default(
+                            src.as_ptr() as *const c_char,
+                            dst.as_mut_ptr() as *mut c_char,
+                            src.len() as c_int,
+                            dst.len() as c_int,
+                        ),
+                    }
+                };
+                if written <= 0 {
+                    return ErrorInternal::new_error("LZ4 compression failed");
+                }
+                Ok(written as usize)
+            }
+            #[allow(unreachable_patterns)]
+            _ => ErrorInternal::new_error("no Compression backend is compiled in"),
+        }
+    }
+
+    fn decompress(&self, src: &[u8], dst: &mut [u8]) -> StdResult<usize, ErrorInternal> {
+        match self {
+            #[cfg(feature = "lz4_flex")]
+            Self::Lz4Flex => match lz4_flex::block::decompress_into(src, dst) {
+                Ok(written) => Ok(written),
+                Err(_) => ErrorInternal::new_error("lz4_flex decompression failed"),
+            },
+            #[cfg(feature = "lz4-sys")]
+            Self::Lz4Sys => {
+                let written = unsafe {
+                    lz4_sys::LZ4_decompress_safe(
+                        src.as_ptr() as *const c_char,
+                        dst.as_mut_ptr() as *mut c_char,
+                        src.len() as c_int,
+                        dst.len() as c_int,
+                    )
+                };
+                if written < 0 {
+                    return ErrorInternal::new_error("LZ4 decompression failed");
+                }
+                Ok(written as usize)
+            }
+            #[allow(unreachable_patterns)]
+            _ => ErrorInternal::new_error("no Compression backend is compiled in"),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "lz4-sys"))]
+mod test_compression {
+    use super::{Compression, Context};
+
+    #[test]
+    fn level_zero_matches_fast_path() {
+        let context = Context::Lz4Sys;
+        let src = b"..................................".repeat(64);
+        let mut dst_fast = vec![0u8; context.get_maximum_compressed_buffer_len(src.len())];
+        let mut dst_level_zero = dst_fast.clone();
+
+        let fast_len = context.compress(&src, &mut dst_fast).unwrap();
+        let level_zero_len = context
+            .compress_level(Some(0), &src, &mut dst_level_zero)
+            .unwrap();
+
+        assert_eq!(&dst_fast[..fast_len], &dst_level_zero[..level_zero_len]);
+    }
+
+    #[test]
+    fn high_compression_round_trips() {
+        let context = Context::Lz4Sys;
+        let src = b"..................................".repeat(64);
+        let mut compressed = vec![0u8; context.get_maximum_compressed_buffer_len(src.len())];
+        let compressed_len = context
+            .compress_level(Some(9), &src, &mut compressed)
+            .unwrap();
+
+        let mut decompressed = vec![0u8; src.len()];
+        let decompressed_len = context
+            .decompress(&compressed[..compressed_len], &mut decompressed)
+            .unwrap();
+
+        assert_eq!(&decompressed[..decompressed_len], src.as_slice());
+    }
+}
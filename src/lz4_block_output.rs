@@ -25,13 +25,40 @@ use std::result::Result as StdResult;
 /// ```
 pub type Lz4BlockOutput<R> = Lz4BlockOutputBase<R, Context>;
 
+/// Magic marker identifying the optional block index footer appended when index collection is
+/// requested. Readers that do not recognize it simply ignore the trailing bytes;
+/// [`crate::lz4_block_seekable_input`] recognizes it and stops walking the block stream there.
+pub(crate) const INDEX_FOOTER_MAGIC: &[u8; 8] = b"LZ4JBIDX";
+
 impl<W: Write> Lz4BlockOutput<W> {
     /// Create a new [`Lz4BlockOutput`] with the default parameters.
     ///
     /// See [`Self::with_context()`]
     #[inline]
     pub fn new(w: W) -> Self {
-        Self::with_context(w, Context::default(), Self::default_block_size()).unwrap()
+        Self::with_context(
+            w,
+            Context::default(),
+            Self::default_block_size(),
+            None,
+            false,
+        )
+        .unwrap()
+    }
+
+    /// Create a new [`Lz4BlockOutput`] with the given compression `effort`.
+    ///
+    /// See [`Self::with_context()`]
+    #[inline]
+    pub fn with_level(w: W, effort: Option<i32>) -> Self {
+        Self::with_context(
+            w,
+            Context::default(),
+            Self::default_block_size(),
+            effort,
+            false,
+        )
+        .unwrap()
     }
 }
 
@@ -43,10 +70,14 @@ pub struct Lz4BlockOutputBase<W: Write + Sized, C: Compression> {
     writer: W,
     compression: C,
     compression_level: CompressionLevel,
+    compression_effort: Option<i32>,
     write_ptr: usize,
     decompressed_buf: Vec<u8>,
     compressed_buf: Vec<u8>,
     checksum: Checksum,
+    bytes_written: u64,
+    decompressed_total: u64,
+    index: Option<Vec<(u64, u64)>>,
 }
 
 impl<W: Write, C: Compression> Lz4BlockOutputBase<W, C> {
@@ -60,13 +91,33 @@ impl<W: Write, C: Compression> Lz4BlockOutputBase<W, C> {
     ///
     /// See [`Self::with_checksum()`]
     #[inline]
-    pub fn with_context(w: W, c: C, block_size: usize) -> std::io::Result<Self> {
-        Self::with_checksum(w, c, block_size, Lz4BlockHeader::default_checksum)
+    pub fn with_context(
+        w: W,
+        c: C,
+        block_size: usize,
+        effort: Option<i32>,
+        with_index: bool,
+    ) -> std::io::Result<Self> {
+        Self::with_checksum(
+            w,
+            c,
+            block_size,
+            effort,
+            with_index,
+            Lz4BlockHeader::default_checksum,
+        )
     }
 
     /// Create a new [`Lz4BlockOutputBase`].
     ///
     /// The `block_size` must be between `64` and `33554432` bytes.
+    /// The `effort` trades CPU for compression ratio: `None` or `Some(0)` keep the fast path,
+    /// higher values ask the underlying [`Compression`] for its high-compression mode, when it
+    /// has one.
+    /// When `with_index` is set, a footer listing the `(decompressed_offset, compressed_offset)`
+    /// of every block is appended once the last block has been written, giving cheap random
+    /// access into the stream without breaking compatibility with plain Java block stream
+    /// readers, which simply ignore the trailing bytes.
     /// The checksum must return a [`u32`].
     ///
     /// # Errors
@@ -76,6 +127,8 @@ impl<W: Write, C: Compression> Lz4BlockOutputBase<W, C> {
         w: W,
         c: C,
         block_size: usize,
+        effort: Option<i32>,
+        with_index: bool,
         checksum: fn(&[u8]) -> u32,
     ) -> std::io::Result<Self> {
         let compression_level = CompressionLevel::from_block_size(block_size)?;
@@ -85,10 +138,14 @@ impl<W: Write, C: Compression> Lz4BlockOutputBase<W, C> {
             writer: w,
             compression: c,
             compression_level,
+            compression_effort: effort,
             write_ptr: 0,
             compressed_buf: vec![0u8; compressed_buf_len],
             decompressed_buf: vec![0u8; block_size],
             checksum: Checksum::new(checksum),
+            bytes_written: 0,
+            decompressed_total: 0,
+            index: if with_index { Some(Vec::new()) } else { None },
         })
     }
 
@@ -125,10 +182,11 @@ impl<W: Write, C: Compression> Lz4BlockOutputBase<W, C> {
     fn flush(&mut self) -> Result<()> {
         if self.write_ptr > 0 {
             let decompressed_buf = &self.decompressed_buf[..self.write_ptr];
-            let compressed_buf = match self
-                .compression
-                .compress(decompressed_buf, self.compressed_buf.as_mut())
-            {
+            let compressed_buf = match self.compression.compress_level(
+                self.compression_effort,
+                decompressed_buf,
+                self.compressed_buf.as_mut(),
+            ) {
                 Ok(s) => &self.compressed_buf[..s],
                 Err(err) => return Err(err.into()),
             };
@@ -138,6 +196,10 @@ impl<W: Write, C: Compression> Lz4BlockOutputBase<W, C> {
                 } else {
                     (CompressionMethod::Raw, decompressed_buf)
                 };
+
+            // Buffer the header locally first so its exact encoded size is known, without
+            // assuming anything about the Java block header layout.
+            let mut header_buf = Vec::new();
             Lz4BlockHeader {
                 compression_method,
                 compression_level: self.compression_level,
@@ -145,13 +207,34 @@ impl<W: Write, C: Compression> Lz4BlockOutputBase<W, C> {
                 decompressed_len: decompressed_buf.len() as u32,
                 checksum: self.checksum.run(decompressed_buf),
             }
-            .write(&mut self.writer)?;
+            .write(&mut header_buf)?;
+
+            if let Some(index) = &mut self.index {
+                index.push((self.decompressed_total, self.bytes_written));
+            }
+            self.bytes_written += header_buf.len() as u64 + buf_to_write.len() as u64;
+            self.decompressed_total += decompressed_buf.len() as u64;
+
+            self.writer.write_all(&header_buf)?;
             self.writer.write_all(buf_to_write)?;
         }
         self.write_ptr = 0;
         self.writer.flush()?;
         Ok(())
     }
+
+    /// Append the block index footer: a magic marker followed by the number of entries and, for
+    /// each block, its `(decompressed_offset, compressed_offset)` as little-endian `u64`s.
+    fn write_footer(&mut self, index: &[(u64, u64)]) -> Result<()> {
+        self.writer.write_all(INDEX_FOOTER_MAGIC)?;
+        self.writer.write_all(&(index.len() as u64).to_le_bytes())?;
+        for (decompressed_offset, compressed_offset) in index {
+            self.writer.write_all(&decompressed_offset.to_le_bytes())?;
+            self.writer.write_all(&compressed_offset.to_le_bytes())?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
 }
 
 impl<W: Write, C: Compression> Write for Lz4BlockOutputBase<W, C> {
@@ -167,6 +250,9 @@ impl<W: Write, C: Compression> Write for Lz4BlockOutputBase<W, C> {
 impl<W: Write, C: Compression> Drop for Lz4BlockOutputBase<W, C> {
     fn drop(&mut self) {
         let _ = self.flush();
+        if let Some(index) = self.index.take() {
+            let _ = self.write_footer(&index);
+        }
     }
 }
 
@@ -189,14 +275,14 @@ mod test_lz4_block_output {
     #[test]
     fn write_empty() {
         let mut out = Vec::<u8>::new();
-        Lz4BlockOutput::with_context(&mut out, Context::default(), 128).unwrap();
+        Lz4BlockOutput::with_context(&mut out, Context::default(), 128, None, false).unwrap();
         assert_eq!(out, []);
     }
 
     #[test]
     fn write_basic() {
         let mut out = Vec::<u8>::new();
-        Lz4BlockOutput::with_context(&mut out, Context::default(), 128)
+        Lz4BlockOutput::with_context(&mut out, Context::default(), 128, None, false)
             .unwrap()
             .write_all("...".as_bytes())
             .unwrap();
@@ -209,9 +295,14 @@ mod test_lz4_block_output {
         let buf = ['.' as u8; 1024];
         let loops = 1024;
         {
-            let mut writer =
-                Lz4BlockOutput::with_context(&mut out, Context::default(), buf.len() * loops)
-                    .unwrap();
+            let mut writer = Lz4BlockOutput::with_context(
+                &mut out,
+                Context::default(),
+                buf.len() * loops,
+                None,
+                false,
+            )
+            .unwrap();
             for _ in 0..loops {
                 writer.write_all(&buf).unwrap();
             }
@@ -233,7 +324,8 @@ mod test_lz4_block_output {
         let loops = 1234;
         {
             let mut writer =
-                Lz4BlockOutput::with_context(&mut out, Context::default(), buf.len()).unwrap();
+                Lz4BlockOutput::with_context(&mut out, Context::default(), buf.len(), None, false)
+                    .unwrap();
             for _ in 0..loops {
                 writer.write_all(&buf).unwrap();
             }
@@ -253,7 +345,8 @@ mod test_lz4_block_output {
         let mut out = Vec::<u8>::new();
         {
             let mut writer =
-                Lz4BlockOutput::with_context(&mut out, Context::default(), 128).unwrap();
+                Lz4BlockOutput::with_context(&mut out, Context::default(), 128, None, false)
+                    .unwrap();
             writer.write_all("...".as_bytes()).unwrap();
             writer.flush().unwrap();
             writer.write_all("...".as_bytes()).unwrap();
@@ -0,0 +1,541 @@
+use crate::common::{ErrorInternal, Result};
+use crate::compression::{Compression, Context};
+use crate::lz4_block_header::{CompressionLevel, CompressionMethod, Lz4BlockHeader};
+use crate::lz4_block_output::Lz4BlockOutputBase;
+
+use std::cmp::min;
+use std::collections::BinaryHeap;
+use std::io;
+use std::io::Write;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+
+/// Wrapper around a [`Write`] object to compress data using a pool of worker threads.
+///
+/// Every block of the Java LZ4 block format is fully independent, so
+/// [`Lz4BlockParallelOutputBase`] hands each filled block to a pool of worker threads and
+/// reassembles the compressed blocks in their original order before writing them to the wrapped
+/// [`Write`]. The resulting stream is byte-identical to the one produced by
+/// [`Lz4BlockOutputBase`].
+///
+/// A block filled through [`Write::write`] is handed to the pool and waited for before the next
+/// one is accepted, so a single writer has at most one block being compressed at a time, whatever
+/// the number of worker threads.
+///
+/// [`Write::flush`] blocks until every block submitted so far has actually been written to the
+/// wrapped [`Write`], not merely handed to the worker pool; [`Drop`] drains the same way before
+/// joining the worker threads.
+///
+/// # Example
+///
+/// ```rust
+/// use lz4jb::Lz4BlockParallelOutput;
+/// use std::io::Write;
+///
+/// fn main() -> std::io::Result<()> {
+///     // The worker threads own the wrapped Write (`W: 'static`), so pass a Vec<u8>, not a `&mut`.
+///     let mut output = Lz4BlockParallelOutput::new(Vec::new(), 4);
+///     output.write_all("...".as_bytes())?;
+///     output.flush()?;
+///     Ok(())
+/// }
+/// ```
+pub type Lz4BlockParallelOutput<W> = Lz4BlockParallelOutputBase<W, Context>;
+
+impl<W: Write + Send + 'static> Lz4BlockParallelOutput<W> {
+    /// Create a new [`Lz4BlockParallelOutput`] with the default parameters.
+    ///
+    /// See [`Self::with_context()`]
+    #[inline]
+    pub fn new(w: W, processes: usize) -> Self {
+        Self::with_level(w, processes, None)
+    }
+
+    /// Create a new [`Lz4BlockParallelOutput`] with the given compression `effort`.
+    ///
+    /// See [`Self::with_context()`]
+    #[inline]
+    pub fn with_level(w: W, processes: usize, effort: Option<i32>) -> Self {
+        Self::with_context(
+            w,
+            Context::default(),
+            Lz4BlockOutputBase::<W, Context>::default_block_size(),
+            processes,
+            effort,
+        )
+        .unwrap()
+    }
+}
+
+/// A filled block handed from the calling thread to the worker pool, tagged with a monotonically
+/// increasing sequence number so the collector can restore the original order.
+struct Job {
+    seq: u64,
+    decompressed: Vec<u8>,
+}
+
+/// A block compressed by a worker thread, still tagged with its sequence number.
+struct CompressedBlock {
+    seq: u64,
+    header: Lz4BlockHeader,
+    buf: Vec<u8>,
+}
+
+impl PartialEq for CompressedBlock {
+    fn eq(&self, other: &Self) -> bool {
+        self.seq == other.seq
+    }
+}
+impl Eq for CompressedBlock {}
+impl PartialOrd for CompressedBlock {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for CompressedBlock {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // Reversed so that `BinaryHeap`, a max-heap, pops the lowest `seq` first.
+        other.seq.cmp(&self.seq)
+    }
+}
+
+/// Wrapper around a [`Write`] object to compress data in parallel.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise
+/// use the alias [`Lz4BlockParallelOutput`].
+pub struct Lz4BlockParallelOutputBase<
+    W: Write + Send + 'static,
+    C: Compression + Copy + Send + 'static,
+> {
+    job_tx: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    collector: Option<JoinHandle<io::Result<W>>>,
+    error: Arc<Mutex<Option<ErrorInternal>>>,
+    /// Number of blocks the collector has fully written to the inner `Write`, paired with a
+    /// [`Condvar`] so [`Self::flush()`] can block until the block it just submitted lands.
+    written: Arc<(Mutex<u64>, Condvar)>,
+    write_ptr: usize,
+    decompressed_buf: Vec<u8>,
+    seq: u64,
+    _compression: std::marker::PhantomData<C>,
+}
+
+impl<W: Write + Send + 'static, C: Compression + Copy + Send + 'static>
+    Lz4BlockParallelOutputBase<W, C>
+{
+    /// Create a new [`Lz4BlockParallelOutputBase`] with the default checksum implementation
+    /// which is compatible with the Java's default implementation, including the missing 4 bits
+    /// bug.
+    ///
+    /// See [`Self::with_checksum()`]
+    #[inline]
+    pub fn with_context(
+        w: W,
+        c: C,
+        block_size: usize,
+        processes: usize,
+        effort: Option<i32>,
+    ) -> io::Result<Self> {
+        Self::with_checksum(
+            w,
+            c,
+            block_size,
+            processes,
+            effort,
+            Lz4BlockHeader::default_checksum,
+        )
+    }
+
+    /// Create a new [`Lz4BlockParallelOutputBase`].
+    ///
+    /// The `block_size` must be between `64` and `33554432` bytes.
+    /// The `processes` must be at least `1`.
+    /// The `effort` trades CPU for compression ratio the same way as
+    /// [`crate::lz4_block_output::Lz4BlockOutputBase::with_checksum`]: each worker calls
+    /// [`Compression::compress_level`] with it independently.
+    /// The checksum must return a [`u32`].
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the `block_size` or the `processes` count is out of range.
+    pub fn with_checksum(
+        w: W,
+        c: C,
+        block_size: usize,
+        processes: usize,
+        effort: Option<i32>,
+        checksum: fn(&[u8]) -> u32,
+    ) -> io::Result<Self> {
+        if processes == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "processes must be at least 1",
+            ));
+        }
+        let compression_level = CompressionLevel::from_block_size(block_size)?;
+        let compressed_buf_len = c
+            .get_maximum_compressed_buffer_len(compression_level.get_max_decompressed_buffer_len());
+        let error = Arc::new(Mutex::new(None));
+
+        // Both channels are bounded to two entries per worker. Every submitted block is waited
+        // for before the next one is accepted (see Self::write() and Self::flush()), so at most
+        // one job is queued at a time.
+        let (job_tx, job_rx) = sync_channel::<Job>(processes * 2);
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let (result_tx, result_rx) = sync_channel::<CompressedBlock>(processes * 2);
+
+        let written = Arc::new((Mutex::new(0u64), Condvar::new()));
+        let workers = (0..processes)
+            .map(|_| {
+                let job_rx = Arc::clone(&job_rx);
+                let result_tx = result_tx.clone();
+                let error = Arc::clone(&error);
+                let written = Arc::clone(&written);
+                std::thread::spawn(move || {
+                    Self::worker_loop(
+                        c,
+                        compression_level,
+                        effort,
+                        checksum,
+                        compressed_buf_len,
+                        &job_rx,
+                        result_tx,
+                        error,
+                        written,
+                    )
+                })
+            })
+            .collect();
+        drop(result_tx);
+
+        let collector = {
+            let written = Arc::clone(&written);
+            std::thread::spawn(move || Self::collector_loop(w, result_rx, written))
+        };
+
+        Ok(Self {
+            job_tx: Some(job_tx),
+            workers,
+            collector: Some(collector),
+            error,
+            written,
+            write_ptr: 0,
+            decompressed_buf: vec![0u8; block_size],
+            seq: 0,
+            _compression: std::marker::PhantomData,
+        })
+    }
+
+    /// Unblock every [`Self::wait_for_written`] caller for good: used once it is known no
+    /// further progress will ever be published, either because the collector ran out of blocks
+    /// to write or because a worker hit an error and the seq it owned will never arrive.
+    fn poison_written(written: &(Mutex<u64>, Condvar)) {
+        let (lock, condvar) = written;
+        *lock.lock().unwrap() = u64::MAX;
+        condvar.notify_all();
+    }
+
+    /// Worker thread body: pull filled blocks off the shared job queue, compress them
+    /// independently of the other workers and hand the result to the collector.
+    ///
+    /// A compression error poisons `written` directly rather than merely dropping this worker's
+    /// `result_tx`: with more than one worker alive, the channel stays open and the collector
+    /// would otherwise keep waiting forever for the `seq` this worker was responsible for.
+    fn worker_loop(
+        compression: C,
+        compression_level: CompressionLevel,
+        effort: Option<i32>,
+        checksum: fn(&[u8]) -> u32,
+        compressed_buf_len: usize,
+        job_rx: &Mutex<Receiver<Job>>,
+        result_tx: SyncSender<CompressedBlock>,
+        error: Arc<Mutex<Option<ErrorInternal>>>,
+        written: Arc<(Mutex<u64>, Condvar)>,
+    ) {
+        let mut compressed_buf = vec![0u8; compressed_buf_len];
+        loop {
+            let job = {
+                let job_rx = job_rx.lock().unwrap();
+                job_rx.recv()
+            };
+            let job = match job {
+                Ok(job) => job,
+                Err(_) => return,
+            };
+            let decompressed_buf = job.decompressed.as_slice();
+            let compressed_len =
+                match compression.compress_level(effort, decompressed_buf, compressed_buf.as_mut())
+                {
+                    Ok(s) => s,
+                    Err(err) => {
+                        *error.lock().unwrap() = Some(err.into());
+                        Self::poison_written(&written);
+                        return;
+                    }
+                };
+            let (compression_method, buf_to_write) = if compressed_len < decompressed_buf.len() {
+                (CompressionMethod::Lz4, &compressed_buf[..compressed_len])
+            } else {
+                (CompressionMethod::Raw, decompressed_buf)
+            };
+            let block = CompressedBlock {
+                seq: job.seq,
+                header: Lz4BlockHeader {
+                    compression_method,
+                    compression_level,
+                    compressed_len: buf_to_write.len() as u32,
+                    decompressed_len: decompressed_buf.len() as u32,
+                    checksum: checksum(decompressed_buf),
+                },
+                buf: buf_to_write.to_vec(),
+            };
+            if result_tx.send(block).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Collector thread body: reorder the compressed blocks through a min-heap keyed by `seq`
+    /// and write them to the inner `Write` strictly in the original order, publishing `written`
+    /// after each one so [`Self::flush()`] can wait for it.
+    fn collector_loop(
+        mut w: W,
+        result_rx: Receiver<CompressedBlock>,
+        written: Arc<(Mutex<u64>, Condvar)>,
+    ) -> io::Result<W> {
+        let mut pending = BinaryHeap::new();
+        let mut next_seq = 0u64;
+        while let Ok(block) = result_rx.recv() {
+            pending.push(block);
+            while matches!(pending.peek(), Some(block) if block.seq == next_seq) {
+                let block = pending.pop().unwrap();
+                block.header.write(&mut w)?;
+                w.write_all(&block.buf)?;
+                next_seq += 1;
+
+                let (lock, condvar) = &*written;
+                *lock.lock().unwrap() = next_seq;
+                condvar.notify_all();
+            }
+        }
+        // No worker will ever send another block (the job queue was closed, or every worker hit
+        // an error and dropped its result sender): unblock any flush() still waiting on a seq
+        // that will now never be written, before attempting the final flush of the inner `Write`.
+        Self::poison_written(&written);
+        w.flush()?;
+        Ok(w)
+    }
+
+    fn check_error(&self) -> Result<()> {
+        match self.error.lock().unwrap().take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.check_error()?;
+        if self.write_ptr == self.decompressed_buf.len() {
+            self.flush()?;
+        }
+        let size_to_copy = min(buf.len(), self.decompressed_buf.len() - self.write_ptr);
+        self.decompressed_buf[self.write_ptr..self.write_ptr + size_to_copy]
+            .copy_from_slice(&buf[..size_to_copy]);
+        self.write_ptr += size_to_copy;
+        Ok(size_to_copy)
+    }
+
+    /// Enqueue the pending partial block, if any, then block until every block submitted so far
+    /// has been compressed, reordered and written to the inner `Write`, honoring the same
+    /// contract as [`Write::flush`].
+    fn flush(&mut self) -> Result<()> {
+        self.check_error()?;
+        if self.write_ptr > 0 {
+            let seq = self.seq;
+            self.seq += 1;
+            let decompressed = self.decompressed_buf[..self.write_ptr].to_vec();
+            self.write_ptr = 0;
+            if let Some(job_tx) = &self.job_tx {
+                if job_tx.send(Job { seq, decompressed }).is_err() {
+                    return self.check_error();
+                }
+            }
+        }
+        self.wait_for_written(self.seq)
+    }
+
+    /// Block until the collector has written every block up to, but not including, `seq`.
+    fn wait_for_written(&self, seq: u64) -> Result<()> {
+        let (lock, condvar) = &*self.written;
+        let mut written = lock.lock().unwrap();
+        while *written < seq {
+            self.check_error()?;
+            written = condvar.wait(written).unwrap();
+        }
+        drop(written);
+        self.check_error()
+    }
+
+    /// Drain every in-flight block: flush the pending partial block, close the job queue so the
+    /// workers exit once it is empty, then join the workers and the collector so the output is
+    /// fully written before this call returns.
+    fn drain(&mut self) -> Result<()> {
+        self.flush()?;
+        self.job_tx.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        if let Some(collector) = self.collector.take() {
+            match collector.join() {
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => return Err(err.into()),
+                Err(_) => {
+                    return Err(io::Error::new(io::ErrorKind::Other, "worker pool panicked").into())
+                }
+            }
+        }
+        self.check_error()
+    }
+}
+
+impl<W: Write + Send + 'static, C: Compression + Copy + Send + 'static> Write
+    for Lz4BlockParallelOutputBase<W, C>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(Self::write(self, buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(Self::flush(self)?)
+    }
+}
+
+impl<W: Write + Send + 'static, C: Compression + Copy + Send + 'static> Drop
+    for Lz4BlockParallelOutputBase<W, C>
+{
+    fn drop(&mut self) {
+        let _ = self.drain();
+    }
+}
+
+#[cfg(test)]
+mod test_lz4_block_parallel_output {
+    use super::{Compression, Context, Lz4BlockParallelOutput, Lz4BlockParallelOutputBase};
+    use crate::common::{ErrorInternal, Result};
+    use crate::lz4_block_output::Lz4BlockOutput;
+
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// A `Write + Send + 'static` sink that stays readable after being handed to the writer:
+    /// `Lz4BlockParallelOutputBase` moves its `W` into a worker thread, so a borrowed `&mut
+    /// Vec<u8>` (whose lifetime is tied to the test function) cannot be used here.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.lock().unwrap().flush()
+        }
+    }
+
+    #[test]
+    fn zero_processes_is_rejected() {
+        assert!(Lz4BlockParallelOutput::with_context(
+            Vec::<u8>::new(),
+            Context::default(),
+            128,
+            0,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn matches_serial_output() {
+        let buf = ['.' as u8; 37];
+        let loops = 200;
+
+        let mut serial_out = Vec::<u8>::new();
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut serial_out, Context::default(), 128, None, false)
+                    .unwrap();
+            for _ in 0..loops {
+                writer.write_all(&buf).unwrap();
+            }
+        }
+
+        let parallel_out = SharedBuf::default();
+        {
+            let mut writer = Lz4BlockParallelOutput::with_context(
+                parallel_out.clone(),
+                Context::default(),
+                128,
+                4,
+                None,
+            )
+            .unwrap();
+            for _ in 0..loops {
+                writer.write_all(&buf).unwrap();
+            }
+        }
+
+        assert_eq!(*parallel_out.0.lock().unwrap(), serial_out);
+    }
+
+    #[derive(Debug, Copy, Clone)]
+    struct FailingCompression;
+
+    impl Compression for FailingCompression {
+        fn get_maximum_compressed_buffer_len(&self, decompressed_len: usize) -> usize {
+            decompressed_len
+        }
+        fn compress(&self, _src: &[u8], _dst: &mut [u8]) -> Result<usize> {
+            ErrorInternal::new_error("forced compression failure")
+        }
+        fn decompress(&self, _src: &[u8], _dst: &mut [u8]) -> Result<usize> {
+            ErrorInternal::new_error("forced decompression failure")
+        }
+    }
+
+    #[test]
+    fn worker_error_propagates_through_flush() {
+        let mut writer = Lz4BlockParallelOutputBase::with_context(
+            Vec::<u8>::new(),
+            FailingCompression,
+            128,
+            1,
+            None,
+        )
+        .unwrap();
+        writer.write_all(&['.' as u8; 128]).unwrap();
+        assert!(writer.flush().is_err());
+    }
+
+    #[test]
+    fn worker_error_propagates_through_write_or_flush_with_multiple_workers() {
+        // With more than one worker alive, a failing worker only drops its own clone of
+        // result_tx: the channel stays open, so waiting for a block must not rely on it closing
+        // to notice the seq it is waiting on will never be written. A write that fills a block
+        // waits for the previous one, so the error can come back from a write; if no write
+        // reports it, flush() must.
+        let mut writer = Lz4BlockParallelOutputBase::with_context(
+            Vec::<u8>::new(),
+            FailingCompression,
+            128,
+            4,
+            None,
+        )
+        .unwrap();
+        let mut writes = Ok(());
+        for _ in 0..8 {
+            writes = writes.and_then(|()| writer.write_all(&['.' as u8; 128]));
+        }
+        assert!(writes.is_err() || writer.flush().is_err());
+    }
+}
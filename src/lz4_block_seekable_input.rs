@@ -0,0 +1,293 @@
+use crate::common::Result;
+use crate::compression::{Compression, Context};
+use crate::lz4_block_header::{CompressionMethod, Lz4BlockHeader};
+use crate::lz4_block_output::INDEX_FOOTER_MAGIC;
+
+use std::cmp::min;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+/// One entry of the block index built by [`Lz4BlockSeekableInputBase`]: the decompressed stream
+/// offset a block starts at, where its header begins in the underlying stream, and how many
+/// decompressed bytes it holds.
+struct BlockIndexEntry {
+    decompressed_offset: u64,
+    compressed_offset: u64,
+    decompressed_len: u32,
+}
+
+/// Wrapper around a [`Read`] + [`Seek`] object to randomly access a Java LZ4 block stream without
+/// decompressing it from the start.
+///
+/// Because every block header stores both its compressed and decompressed length, the
+/// decompressed offset of every block boundary can be computed by scanning the headers alone.
+/// [`Lz4BlockSeekableInputBase`] builds that index once, on construction. [`Seek`] only records
+/// the new position; the next [`Read::read`] finds the containing block with a binary search and
+/// decompresses only that block.
+///
+/// # Example
+///
+/// ```rust
+/// use lz4jb::Lz4BlockSeekableInput;
+/// use std::io::{Read, Seek, SeekFrom};
+///
+/// fn main() -> std::io::Result<()> {
+///     let compressed = std::io::Cursor::new(Vec::<u8>::new());
+///     let mut input = Lz4BlockSeekableInput::new(compressed)?;
+///     input.seek(SeekFrom::Start(0))?;
+///     let mut buf = [0u8; 16];
+///     input.read(&mut buf)?;
+///     Ok(())
+/// }
+/// ```
+pub type Lz4BlockSeekableInput<R> = Lz4BlockSeekableInputBase<R, Context>;
+
+impl<R: Read + Seek> Lz4BlockSeekableInput<R> {
+    /// Create a new [`Lz4BlockSeekableInput`] with the default parameters.
+    ///
+    /// See [`Self::with_context()`]
+    #[inline]
+    pub fn new(r: R) -> io::Result<Self> {
+        Self::with_context(r, Context::default())
+    }
+}
+
+/// Wrapper around a [`Read`] + [`Seek`] object to randomly access compressed data.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise
+/// use the alias [`Lz4BlockSeekableInput`].
+pub struct Lz4BlockSeekableInputBase<R: Read + Seek, C: Compression> {
+    reader: R,
+    compression: C,
+    index: Vec<BlockIndexEntry>,
+    decompressed_len: u64,
+    decompressed_buf: Vec<u8>,
+    compressed_buf: Vec<u8>,
+    current_block: Option<usize>,
+    buf_ptr: usize,
+    position: u64,
+}
+
+impl<R: Read + Seek, C: Compression> Lz4BlockSeekableInputBase<R, C> {
+    /// Create a new [`Lz4BlockSeekableInputBase`].
+    ///
+    /// The stream is walked header-by-header once, upfront, to build the block index; no
+    /// payload is decompressed during construction.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if a block header cannot be read or is malformed.
+    pub fn with_context(mut r: R, c: C) -> io::Result<Self> {
+        let index = Self::build_index(&mut r)?;
+        let decompressed_len = index
+            .last()
+            .map(|entry| entry.decompressed_offset + entry.decompressed_len as u64)
+            .unwrap_or(0);
+        Ok(Self {
+            reader: r,
+            compression: c,
+            index,
+            decompressed_len,
+            decompressed_buf: Vec::new(),
+            compressed_buf: Vec::new(),
+            current_block: None,
+            buf_ptr: 0,
+            position: 0,
+        })
+    }
+
+    /// Walk the stream header-by-header, skipping over the compressed payloads, to build the
+    /// offset index without decompressing anything.
+    fn build_index(r: &mut R) -> io::Result<Vec<BlockIndexEntry>> {
+        let mut index = Vec::new();
+        let mut decompressed_offset = 0u64;
+        loop {
+            let compressed_offset = r.stream_position()?;
+            // A clean end of stream can only happen between two blocks: peek 8 bytes, enough to
+            // tell apart a truncated header, the optional trailing index footer (which starts
+            // with INDEX_FOOTER_MAGIC and is not itself a block), and a real block header.
+            let mut peek = [0u8; INDEX_FOOTER_MAGIC.len()];
+            let mut peeked = 0;
+            while peeked < peek.len() {
+                match r.read(&mut peek[peeked..]) {
+                    Ok(0) => break,
+                    Ok(n) => peeked += n,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            // Fewer than 8 bytes but more than none is a truncated header: fall through so that
+            // Lz4BlockHeader::read() below reports it.
+            if peeked == 0 || &peek == INDEX_FOOTER_MAGIC {
+                break;
+            }
+            r.seek(SeekFrom::Start(compressed_offset))?;
+
+            let header = Lz4BlockHeader::read(r).map_err(io::Error::from)?;
+            let payload_offset = r.stream_position()?;
+            index.push(BlockIndexEntry {
+                decompressed_offset,
+                compressed_offset,
+                decompressed_len: header.decompressed_len,
+            });
+            decompressed_offset += header.decompressed_len as u64;
+            r.seek(SeekFrom::Start(
+                payload_offset + header.compressed_len as u64,
+            ))?;
+        }
+        Ok(index)
+    }
+
+    /// Find the index of the block containing the given decompressed offset.
+    fn block_for_offset(&self, offset: u64) -> Option<usize> {
+        if offset >= self.decompressed_len {
+            return None;
+        }
+        match self
+            .index
+            .binary_search_by(|entry| entry.decompressed_offset.cmp(&offset))
+        {
+            Ok(idx) => Some(idx),
+            Err(0) => None,
+            Err(idx) => Some(idx - 1),
+        }
+    }
+
+    /// Seek the inner reader to the given block and decompress it entirely into
+    /// `decompressed_buf`.
+    fn load_block(&mut self, idx: usize) -> Result<()> {
+        if self.current_block == Some(idx) {
+            return Ok(());
+        }
+        let entry = &self.index[idx];
+        self.reader.seek(SeekFrom::Start(entry.compressed_offset))?;
+        let header = Lz4BlockHeader::read(&mut self.reader)?;
+
+        let compressed_len = header.compressed_len as usize;
+        if self.compressed_buf.len() < compressed_len {
+            self.compressed_buf.resize(compressed_len, 0);
+        }
+        self.reader
+            .read_exact(&mut self.compressed_buf[..compressed_len])?;
+
+        let decompressed_len = header.decompressed_len as usize;
+        if self.decompressed_buf.len() < decompressed_len {
+            self.decompressed_buf.resize(decompressed_len, 0);
+        }
+        match header.compression_method {
+            CompressionMethod::Raw => {
+                self.decompressed_buf[..decompressed_len]
+                    .copy_from_slice(&self.compressed_buf[..compressed_len]);
+            }
+            CompressionMethod::Lz4 => {
+                self.compression.decompress(
+                    &self.compressed_buf[..compressed_len],
+                    &mut self.decompressed_buf[..decompressed_len],
+                )?;
+            }
+        }
+
+        self.current_block = Some(idx);
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek, C: Compression> Read for Lz4BlockSeekableInputBase<R, C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let idx = match self.block_for_offset(self.position) {
+            Some(idx) => idx,
+            None => return Ok(0),
+        };
+        self.load_block(idx)?;
+
+        let entry = &self.index[idx];
+        self.buf_ptr = (self.position - entry.decompressed_offset) as usize;
+        let available = entry.decompressed_len as usize - self.buf_ptr;
+        let size_to_copy = min(buf.len(), available);
+        buf[..size_to_copy]
+            .copy_from_slice(&self.decompressed_buf[self.buf_ptr..self.buf_ptr + size_to_copy]);
+        self.position += size_to_copy as u64;
+        Ok(size_to_copy)
+    }
+}
+
+impl<R: Read + Seek, C: Compression> Seek for Lz4BlockSeekableInputBase<R, C> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(p) => p as i64,
+            SeekFrom::End(p) => self.decompressed_len as i64 + p,
+            SeekFrom::Current(p) => self.position as i64 + p,
+        };
+        if new_position < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            ));
+        }
+        self.position = new_position as u64;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod test_lz4_block_seekable_input {
+    use super::{Lz4BlockSeekableInput, Read, Seek, SeekFrom};
+    use crate::compression::Context;
+    use crate::lz4_block_output::Lz4BlockOutput;
+
+    use std::io::{Cursor, Write};
+
+    fn compressed(block_size: usize, with_index: bool, loops: usize) -> Vec<u8> {
+        let buf = ['.' as u8; 37];
+        let mut out = Vec::<u8>::new();
+        {
+            let mut writer = Lz4BlockOutput::with_context(
+                &mut out,
+                Context::default(),
+                block_size,
+                None,
+                with_index,
+            )
+            .unwrap();
+            for _ in 0..loops {
+                writer.write_all(&buf).unwrap();
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn reads_full_content_back() {
+        let compressed = compressed(128, false, 50);
+        let mut input = Lz4BlockSeekableInput::new(Cursor::new(compressed)).unwrap();
+        let mut out = Vec::new();
+        input.read_to_end(&mut out).unwrap();
+        assert_eq!(out, ['.' as u8; 37 * 50].to_vec());
+    }
+
+    #[test]
+    fn index_footer_is_skipped_without_error() {
+        let compressed = compressed(128, true, 50);
+        let input = Lz4BlockSeekableInput::new(Cursor::new(compressed)).unwrap();
+        assert_eq!(input.decompressed_len, 37 * 50);
+    }
+
+    #[test]
+    fn seek_and_read_middle_block() {
+        let compressed = compressed(128, false, 50);
+        let mut input = Lz4BlockSeekableInput::new(Cursor::new(compressed)).unwrap();
+        input.seek(SeekFrom::Start(37 * 10 + 5)).unwrap();
+        let mut buf = [0u8; 4];
+        input.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, ['.' as u8; 4]);
+    }
+
+    #[test]
+    fn seek_past_end_reads_zero() {
+        let compressed = compressed(128, false, 50);
+        let mut input = Lz4BlockSeekableInput::new(Cursor::new(compressed)).unwrap();
+        input.seek(SeekFrom::End(1000)).unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(input.read(&mut buf).unwrap(), 0);
+    }
+}